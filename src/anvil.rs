@@ -0,0 +1,205 @@
+//! Reading and writing the vanilla Anvil (`.mca`) region format, so
+//! `Region` can act as the common in-memory model for both it and
+//! `.linear`.
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{detect_compression_scheme, Chunk, Region};
+
+const SECTOR_SIZE: usize = 4096;
+const LOCATION_TABLE_SECTORS: usize = 1;
+const TIMESTAMP_TABLE_SECTORS: usize = 1;
+
+/// Reads a vanilla `.mca` file into a [`Region`].
+pub fn open_anvil(path: &str) -> Result<Region, Box<dyn Error>> {
+    let coords: Vec<&str> = path.split('/').next_back().unwrap().split('.').collect();
+    let region_x: usize = coords[1].parse::<usize>()?;
+    let region_z: usize = coords[2].parse::<usize>()?;
+
+    let file = File::open(path)?;
+    let mut buffer = BufReader::new(file);
+
+    let mut locations = [(0u32, 0u8); 1024];
+    for location in locations.iter_mut() {
+        let raw = buffer.read_u32::<BigEndian>()?;
+        let sector_offset = raw >> 8;
+        let sector_count = (raw & 0xFF) as u8;
+        *location = (sector_offset, sector_count);
+    }
+
+    let mut timestamps = vec![0i32; 1024];
+    for timestamp in timestamps.iter_mut() {
+        *timestamp = buffer.read_i32::<BigEndian>()?;
+    }
+
+    let mut chunks: Vec<Option<Chunk>> = vec![None; 1024];
+    let mut newest_timestamp: i64 = 0;
+
+    for i in 0..1024 {
+        let (sector_offset, sector_count) = locations[i];
+        if sector_offset == 0 && sector_count == 0 {
+            continue; // Chunk not generated
+        }
+
+        buffer.seek(SeekFrom::Start(sector_offset as u64 * SECTOR_SIZE as u64))?;
+        let length = buffer.read_u32::<BigEndian>()?; // Scheme byte + compressed data
+        if length == 0 {
+            return Err(format!(
+                "Chunk ({}, {}) has a zero-length header",
+                32 * region_x + i % 32,
+                32 * region_z + i / 32
+            )
+            .into());
+        }
+        // Skip the compression scheme byte: Chunk.raw_chunk keeps only the
+        // compressed payload, whose own magic number is enough to recover it.
+        buffer.seek(SeekFrom::Current(1))?;
+        let mut raw_chunk = vec![0u8; length as usize - 1];
+        buffer.read_exact(&mut raw_chunk)?;
+
+        let x = 32 * region_x + i % 32;
+        let z = 32 * region_z + i / 32;
+        chunks[i] = Some(Chunk { raw_chunk, x, z });
+
+        newest_timestamp = newest_timestamp.max(timestamps[i] as i64);
+    }
+
+    Ok(Region {
+        chunks,
+        region_x,
+        region_z,
+        timestamps,
+        newest_timestamp,
+    })
+}
+
+impl Region {
+    /// Writes this region out as a vanilla `r.<x>.<z>.mca` file.
+    pub fn write_anvil(&self, dir: &str) -> Result<(), Box<dyn Error>> {
+        let path = format!("{}/r.{}.{}.mca", dir, self.region_x, self.region_z);
+        let wip_path = format!("{}/r.{}.{}.mca.wip", dir, self.region_x, self.region_z);
+
+        let mut locations = [0u32; 1024];
+        let mut sectors: Vec<u8> = Vec::new();
+        let mut next_sector = LOCATION_TABLE_SECTORS + TIMESTAMP_TABLE_SECTORS;
+
+        for (location, chunk) in locations.iter_mut().zip(self.chunks.iter()) {
+            let Some(chunk) = chunk else {
+                continue;
+            };
+            let scheme = detect_compression_scheme(&chunk.raw_chunk)
+                .ok_or("Cannot determine compression scheme for chunk")?;
+
+            let length = 1 + chunk.raw_chunk.len() as u32; // Scheme byte + payload
+            sectors.write_u32::<BigEndian>(length)?;
+            sectors.write_u8(scheme)?;
+            sectors.extend_from_slice(&chunk.raw_chunk);
+
+            let padding = (SECTOR_SIZE - (sectors.len() % SECTOR_SIZE)) % SECTOR_SIZE;
+            sectors.extend(std::iter::repeat_n(0u8, padding));
+
+            let sector_count = (4 + length as usize).div_ceil(SECTOR_SIZE);
+            if sector_count > 255 {
+                return Err(format!(
+                    "Chunk ({}, {}) needs {} sectors, which doesn't fit Anvil's 1-byte sector count (max 255); vanilla would fall back to an external .mcc file, which this writer doesn't support",
+                    chunk.x, chunk.z, sector_count
+                )
+                .into());
+            }
+            *location = ((next_sector as u32) << 8) | sector_count as u32;
+            next_sector += sector_count;
+        }
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&wip_path)?;
+        let mut buffer = BufWriter::new(file);
+
+        for &location in locations.iter() {
+            buffer.write_u32::<BigEndian>(location)?;
+        }
+        for &timestamp in self.timestamps.iter() {
+            buffer.write_i32::<BigEndian>(timestamp)?;
+        }
+        buffer.write_all(&sectors)?;
+
+        buffer.flush()?;
+        fs::rename(wip_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_with_chunks(region_x: usize, region_z: usize) -> Region {
+        let mut chunks = vec![None; 1024];
+        let mut timestamps = vec![0i32; 1024];
+        for i in 0..5usize {
+            let mut raw_chunk = vec![0x78u8]; // zlib magic, so detect_compression_scheme succeeds
+            raw_chunk.extend((0..64u32).map(|b| (b + i as u32) as u8));
+            chunks[i] = Some(Chunk {
+                raw_chunk,
+                x: 32 * region_x + i % 32,
+                z: 32 * region_z + i / 32,
+            });
+            timestamps[i] = 100 + i as i32;
+        }
+        Region {
+            chunks,
+            region_x,
+            region_z,
+            timestamps,
+            newest_timestamp: 104,
+        }
+    }
+
+    #[test]
+    fn write_anvil_open_anvil_roundtrip() {
+        let dir = format!("{}/linearify_test_anvil_roundtrip", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_anvil(&dir).unwrap();
+
+        let reopened = open_anvil(&format!("{}/r.0.0.mca", dir)).unwrap();
+        for i in 0..5usize {
+            let original = region.chunks[i].as_ref().unwrap();
+            let read_back = reopened.chunks[i].as_ref().unwrap();
+            assert_eq!(original.raw_chunk, read_back.raw_chunk);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_anvil_rejects_zero_length_chunk_header() {
+        let dir = format!("{}/linearify_test_anvil_zerolen", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Hand-craft a minimal .mca: chunk 0 points at sector 2 (past the
+        // location+timestamp tables), whose 4-byte length header is 0.
+        let path = format!("{}/r.0.0.mca", dir);
+        let mut bytes = vec![0u8; 3 * SECTOR_SIZE];
+        let location: u32 = (2 << 8) | 1; // sector_offset = 2, sector_count = 1
+        bytes[0..4].copy_from_slice(&location.to_be_bytes());
+        // bytes[2*SECTOR_SIZE..2*SECTOR_SIZE+4] is already all zero (length = 0)
+        fs::write(&path, &bytes).unwrap();
+
+        let err = open_anvil(&path).unwrap_err();
+        assert!(err.to_string().contains("zero-length"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! Cross-region content-defined deduplication store.
+//!
+//! Regions that share large runs of identical chunk bytes (common across
+//! worlds with copy-pasted structures, or repeated superflat/void chunks)
+//! don't need every copy written out in full. This backend splits each
+//! region's raw body with FastCDC, hashes the resulting segments, and
+//! stores each unique segment once in a content-addressed pack directory.
+//! A region is then just an ordered list of segment hashes.
+
+use std::error::Error;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{decode_region_body, Region, LINEAR_SIGNATURE};
+
+// FastCDC cut-point bounds. 2 KiB / 8 KiB / 64 KiB keeps segments large
+// enough to amortize per-segment overhead while still finding duplicate
+// runs inside a region.
+const MIN_SIZE: usize = 2 * 1024;
+const AVG_SIZE: usize = 8 * 1024;
+const MAX_SIZE: usize = 64 * 1024;
+
+// log2(AVG_SIZE). `MASK_S` has more one-bits than a mask built from this
+// alone would (stricter, lower cut probability) so short segments are rare
+// before the average is reached; `MASK_L` has fewer (looser, higher cut
+// probability) so segments are forced to close in before `MAX_SIZE`.
+const AVG_BITS: u32 = 13;
+const MASK_S: u64 = (1u64 << (AVG_BITS + 2)) - 1;
+const MASK_L: u64 = (1u64 << (AVG_BITS - 2)) - 1;
+
+const HASH_SIZE: usize = 32; // blake3 digest
+
+/// 256 fixed pseudo-random values used to roll FastCDC's Gear hash.
+/// Generated once with a seeded SplitMix64 so cut points are stable across
+/// runs and across regions, which is what makes the same repeated byte run
+/// dedup to the same segment hash no matter where it appears.
+const GEAR: [u64; 256] = [
+    0x6E789E6AA1B965F4, 0x06C45D188009454F, 0xF88BB8A8724C81EC, 0x1B39896A51A8749B,
+    0x53CB9F0C747EA2EA, 0x2C829ABE1F4532E1, 0xC584133AC916AB3C, 0x3EE5789041C98AC3,
+    0xF3B8488C368CB0A6, 0x657EECDD3CB13D09, 0xC2D326E0055BDEF6, 0x8621A03FE0BBDB7B,
+    0x8E1F7555983AA92F, 0xB54E0F1600CC4D19, 0x84BB3F97971D80AB, 0x7D29825C75521255,
+    0xC3CF17102B7F7F86, 0x3466E9A083914F64, 0xD81A8D2B5A4485AC, 0xDB01602B100B9ED7,
+    0xA9038A921825F10D, 0xEDF5F1D90DCA2F6A, 0x54496AD67BD2634C, 0xDD7C01D4F5407269,
+    0x935E82F1DB4C4F7B, 0x69B82EBC92233300, 0x40D29EB57DE1D510, 0xA2F09DABB45C6316,
+    0xEE521D7A0F4D3872, 0xF16952EE72F3454F, 0x377D35DEA8E40225, 0x0C7DE8064963BAB0,
+    0x05582D37111AC529, 0xD254741F599DC6F7, 0x69630F7593D108C3, 0x417EF96181DAA383,
+    0x3C3C41A3B43343A1, 0x6E19905DCBE531DF, 0x4FA9FA7324851729, 0x84EB4454A792922A,
+    0x134F7096918175CE, 0x07DC930B302278A8, 0x12C015A97019E937, 0xCC06C31652EBF438,
+    0xECEE65630A691E37, 0x3E84ECB1763E79AD, 0x690ED476743AAE49, 0x774615D7B1A1F2E1,
+    0x22B353F04F4F52DA, 0xE3DDD86BA71A5EB1, 0xDF268ADEB6513356, 0x2098EB73D4367D77,
+    0x03D6845323CE3C71, 0xC952C5620043C714, 0x9B196BCA844F1705, 0x30260345DD9E0EC1,
+    0xCF448A5882BB9698, 0xF4A578DCCBC87656, 0xBFDEAED9A17B3C8F, 0xED79402D1D5C5D7B,
+    0x55F070AB1CBBF170, 0x3E00A34929A88F1D, 0xE255B237B8BB18FB, 0x2A7B67AF6C6AD50E,
+    0x466D5E7F3E46F143, 0x42375CB399A4FC72, 0x8C8A1F148A8BB259, 0x32FCAB5DAED5BDFC,
+    0x9E60398C8D8553C0, 0xEE89CCEB8C4064C0, 0xDB0215941D86A66F, 0x5CCDE78203C367A8,
+    0xF1BCBC6A1EC11786, 0xEF054FCEEE954551, 0xDF82012D0555C6DF, 0x292566FF72403C08,
+    0xC4DD302A1BFA1137, 0xD85F219DB5C554E1, 0x6A27FF807441BCD2, 0x96A573E9B48216E8,
+    0x46A9FDAC40BF0048, 0x3DD12464A0EE15B4, 0x451E521296A7EEA1, 0x56E4398A98F8A0FD,
+    0x7B7DC2160E3335A7, 0xC679EE0BEBCB1CCA, 0x928D6F2D7453424E, 0x1B38994205234C6D,
+    0x8086D193A6F2B568, 0x21C6E26639AC2C65, 0xD9DCCAC414D23C6F, 0x91CD642057E00235,
+    0x77FC607DC6589373, 0x05B8ABE26DD3AEE7, 0x12F6436AC376CC66, 0x64952424897B2307,
+    0xEE8C2BAF6343E5C3, 0xDC4C613D9EBA2304, 0x3505B7796BD1A506, 0x8176DAF800A05F50,
+    0x8BD8FF7A0385CDBC, 0x1A764A3CD78101DA, 0xBE4D15BF6CA266AC, 0xA85E1F38BB2DC749,
+    0x56759A968493CD8C, 0xF3A9BCE7336BD182, 0x365B15013741519B, 0x1F7A44A6B109AC94,
+    0x3521D628813CB177, 0x6A77AFAB0F7C9370, 0x179642D8CDE95015, 0x5EF102A8FB354461,
+    0xF51C504764ED82F2, 0xC58427F041CE6808, 0xFAD8FC45C9643C37, 0xCF8682F9A70FA9C0,
+    0x7E1B3B75A4005729, 0x992DD867927B52D8, 0x7FBD5DB142F6791F, 0x370595AACAB4ADAE,
+    0xB1392DBDC5AB61D6, 0x9FEA7DFC79D452D9, 0x40B12B120085641C, 0xA192AFE3157C85D0,
+    0xC847729F4E08F3A3, 0x6F1384A306C41FC2, 0x12D05C4045A39C19, 0x9899202FD20F0841,
+    0xE9C7191857E774B8, 0x4EEAD809AF5B0CC3, 0xE809ACAFA23864A4, 0x4DA1EDABA1D0F7BD,
+    0x846EB9673349F8E4, 0x87BAE55B86039FE8, 0x7F367B8BD953EFF2, 0x3884700F650D04E1,
+    0xBFE4B2AB46980CAD, 0xC5FC89075299106C, 0x37B2FA361ADEA7CD, 0x7D75D813F04895B4,
+    0x702F5B393F62C0E0, 0x0A3FC775F4ECF37F, 0xE4B23787A352437F, 0xF83FA245C34D6363,
+    0xB99BCF040786CF50, 0x38B6EA0A0E6C9D8A, 0x093FDC76776E37E1, 0x1A75E6F76BA7EEE8,
+    0x442CDCFEE9660C62, 0x22D58D35116B5E0B, 0x87D4A5180F6A3645, 0x589FB216BD82131B,
+    0x91D031CAD319AEC0, 0xABECF76A553D320B, 0xB8686CB347612DCF, 0xFCAB66337C0A77F5,
+    0xAC318214381EC437, 0x6EB7F0FCA24494AE, 0xCF42861DCDC895A9, 0x4ABAD7A1586D7A91,
+    0xC21B318DC2F49745, 0xD49474DC2ACBD1F0, 0xB1D4873747C1C8E1, 0x5434DC8C7D015BF6,
+    0xE1C486287511B6A9, 0xA8616DF62E89A193, 0x31CE6319498D8347, 0xAFD0B486123D6FAA,
+    0xE6495F5D102301EB, 0x0DC51CED17A43C52, 0x8BCBCDE81355EF2D, 0x2412AF73FDEE7CFC,
+    0xC8D589E486E29EED, 0x23390E8664517F89, 0x251ADE58E8A6849D, 0xF8555DBD2E8F9CB0,
+    0xCB417C3EEF54F7C3, 0x8028F8E1AAC3A919, 0x10E31052ACF748A0, 0x2D886C073B1E1B78,
+    0x972974D90DF9FAEE, 0xBC1B7B38796893BA, 0x1958ED432070E652, 0xCA5F297197A12DCC,
+    0xE025A27375704F28, 0x418010A570A924FB, 0x9828E2941BFC419C, 0x4FBACD2F52B85C1F,
+    0x33DD5B756211CC67, 0x23C8DFDD1DB57FF0, 0x32F81801A1A8E901, 0x26884EAC5ADA36DA,
+    0xCAA82F9BB42E37D4, 0x19FB1A7491D6A7D1, 0x5AA0243AA357F38E, 0xB31D917809E447F0,
+    0x3F9C197225215BE0, 0xDC3C315A1E33C095, 0x3DD399AD533E80AC, 0x566F32CCE8301D95,
+    0xC880188083D9BA21, 0xB9CC357F3B0E7D2E, 0x0237D2123A8A8D6C, 0xBF636E9AA7CBF6BD,
+    0xD7BD4284C4E2A6A7, 0xDA2EBB47D50577A9, 0x90BA1C11B539087D, 0x44993D31552B4F57,
+    0x32C2D6F80A8A8898, 0x450583ED7FB54B19, 0xEC2B0B09E50EF3EF, 0xD918A0B6E2EFD65C,
+    0xE37A868D9785F572, 0x7D1A6118F2B0F37A, 0x9E2E3CC13B343439, 0xEFD82C11212E37E8,
+    0xAF89C05CD4FC75ED, 0x55BC16BB9697108E, 0x6C4701FA5DB69BEE, 0x9237338441DAF445,
+    0x248CF0831E81A5FC, 0xACC13557E77DE273, 0x520970C25E06513A, 0x657329CB02987CAB,
+    0xA9B0B3366A4E55A8, 0xC4D06CA2F39ACDD4, 0x5DCE37D68170CDE1, 0x5F1E44E77E1854C9,
+    0x6883D452D55DF899, 0x05C5BD62F1067032, 0xE680B683CE60FAB0, 0x5DC9DA3F286D18B1,
+    0x94B4BF3AB85ED6D8, 0xCE65F449E3ACC5A3, 0x34B0209642CEA639, 0xC14C3C771D904827,
+    0x6ADDCEE2BD9CDEE5, 0xE24EED137FFBB613, 0x75DD58EF79963D1B, 0xFDB83ECF6CC24920,
+    0x7A1D0057C57169FB, 0x339200F4FEB62D07, 0xD33F4D4AC88469F4, 0x8226F234E68DFEE4,
+    0x320DEF4F2A105536, 0x7786F3B13AEFC159, 0xB28225AC9DF63EE2, 0x781B9D0376CC6044,
+    0x05BD0115226C6AB6, 0xD302230207BDFDAB, 0xDB898ABD8E0D2933, 0x9E79A397BA00B9CC,
+    0x89DF84A5F0003EE8, 0x011F04F2A75FB9BE, 0x5A5832BB47BCF19E, 0xCBDC6D34B7C7534D,
+];
+
+/// Splits `data` into content-defined chunks using FastCDC with a Gear
+/// rolling hash, returning each segment's `(start, end)` byte range.
+fn fastcdc_cut_points(data: &[u8]) -> Vec<(usize, usize)> {
+    let n = data.len();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    while start < n {
+        let min_end = (start + MIN_SIZE).min(n);
+        let avg_end = (start + AVG_SIZE).min(n);
+        let max_end = (start + MAX_SIZE).min(n);
+
+        let mut fp: u64 = 0;
+        for &byte in &data[start..min_end] {
+            fp = (fp << 1).wrapping_add(GEAR[byte as usize]);
+        }
+
+        let mut cut = max_end;
+        let mut found = false;
+
+        if min_end >= max_end {
+            cut = max_end;
+        } else {
+            for (i, &byte) in data[min_end..avg_end].iter().enumerate() {
+                fp = (fp << 1).wrapping_add(GEAR[byte as usize]);
+                if fp & MASK_S == 0 {
+                    cut = min_end + i + 1;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                for (i, &byte) in data[avg_end..max_end].iter().enumerate() {
+                    fp = (fp << 1).wrapping_add(GEAR[byte as usize]);
+                    if fp & MASK_L == 0 {
+                        cut = avg_end + i + 1;
+                        break;
+                    }
+                }
+            }
+        }
+
+        ranges.push((start, cut));
+        start = cut;
+    }
+
+    ranges
+}
+
+fn object_path(store_dir: &str, hash: &[u8; HASH_SIZE]) -> (String, String) {
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    let dir = format!("{}/objects/{}", store_dir, &hex[0..2]);
+    let path = format!("{}/{}", dir, &hex[2..]);
+    (dir, path)
+}
+
+/// Splits `region`'s raw body into FastCDC segments and writes any segment
+/// not already present in `store_dir`'s object store, then records the
+/// region as an ordered list of segment hashes in its manifest.
+pub fn write_dedup(region: &Region, store_dir: &str) -> Result<(), Box<dyn Error>> {
+    let raw_data = region.build_raw_data();
+    let segments = fastcdc_cut_points(&raw_data);
+
+    let mut hashes: Vec<[u8; HASH_SIZE]> = Vec::with_capacity(segments.len());
+    for (start, end) in &segments {
+        let hash: [u8; HASH_SIZE] = blake3::hash(&raw_data[*start..*end]).into();
+        let (dir, path) = object_path(store_dir, &hash);
+        if fs::metadata(&path).is_err() {
+            fs::create_dir_all(&dir)?;
+            let wip_path = format!("{}.wip", path);
+            fs::write(&wip_path, &raw_data[*start..*end])?;
+            fs::rename(wip_path, path)?;
+        }
+        hashes.push(hash);
+    }
+
+    let manifest_path = format!("{}/r.{}.{}.manifest", store_dir, region.region_x, region.region_z);
+    let wip_path = format!("{}.wip", manifest_path);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&wip_path)?;
+    let mut buffer = BufWriter::new(file);
+
+    buffer.write_i64::<BigEndian>(LINEAR_SIGNATURE)?;
+    buffer.write_i64::<BigEndian>(region.newest_timestamp)?;
+    buffer.write_u32::<BigEndian>(hashes.len() as u32)?;
+    for hash in &hashes {
+        buffer.write_all(hash)?;
+    }
+
+    buffer.flush()?;
+    fs::rename(wip_path, manifest_path)?;
+    Ok(())
+}
+
+/// Reassembles a region from `store_dir`'s manifest and object store,
+/// concatenating segments in order before running the usual body parse.
+pub fn open_dedup(store_dir: &str, region_x: usize, region_z: usize) -> Result<Region, Box<dyn Error>> {
+    let manifest_path = format!("{}/r.{}.{}.manifest", store_dir, region_x, region_z);
+    let file = fs::File::open(&manifest_path)?;
+    let mut buffer = BufReader::new(file);
+
+    let signature = buffer.read_i64::<BigEndian>()?;
+    if signature != LINEAR_SIGNATURE {
+        return Err(format!("Invalid manifest signature: {}", signature).into());
+    }
+    let newest_timestamp = buffer.read_i64::<BigEndian>()?;
+    let segment_count = buffer.read_u32::<BigEndian>()?;
+
+    let mut raw_data = Vec::new();
+    for _ in 0..segment_count {
+        let mut hash = [0u8; HASH_SIZE];
+        buffer.read_exact(&mut hash)?;
+        let (_, path) = object_path(store_dir, &hash);
+        let segment = fs::read(&path)?;
+        if blake3::hash(&segment).as_bytes() != &hash {
+            return Err(format!("Segment {} failed its content hash check", path).into());
+        }
+        raw_data.extend_from_slice(&segment);
+    }
+
+    let (chunks, timestamps, _) = decode_region_body(region_x, region_z, &raw_data)?;
+
+    Ok(Region {
+        chunks,
+        region_x,
+        region_z,
+        timestamps,
+        newest_timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chunk;
+
+    fn region_with_chunks(region_x: usize, region_z: usize) -> Region {
+        let mut chunks = vec![None; 1024];
+        let mut timestamps = vec![0i32; 1024];
+        for i in 0..5usize {
+            chunks[i] = Some(Chunk {
+                raw_chunk: (0..64).map(|b| (b + i) as u8).collect(),
+                x: 32 * region_x + i % 32,
+                z: 32 * region_z + i / 32,
+            });
+            timestamps[i] = 100 + i as i32;
+        }
+        Region {
+            chunks,
+            region_x,
+            region_z,
+            timestamps,
+            newest_timestamp: 104,
+        }
+    }
+
+    #[test]
+    fn write_dedup_open_dedup_roundtrip() {
+        let store_dir = format!("{}/linearify_test_dedup_roundtrip", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&store_dir);
+        fs::create_dir_all(&store_dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        write_dedup(&region, &store_dir).unwrap();
+
+        let reopened = open_dedup(&store_dir, 0, 0).unwrap();
+        for i in 0..5usize {
+            let original = region.chunks[i].as_ref().unwrap();
+            let read_back = reopened.chunks[i].as_ref().unwrap();
+            assert_eq!(original.raw_chunk, read_back.raw_chunk);
+        }
+        assert_eq!(reopened.newest_timestamp, region.newest_timestamp);
+
+        fs::remove_dir_all(&store_dir).unwrap();
+    }
+
+    #[test]
+    fn open_dedup_rejects_tampered_segment() {
+        let store_dir = format!("{}/linearify_test_dedup_tamper", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&store_dir);
+        fs::create_dir_all(&store_dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        write_dedup(&region, &store_dir).unwrap();
+
+        // Tamper with an arbitrary byte of an arbitrary stored object so its
+        // content no longer matches the hash that names it.
+        let objects_dir = format!("{}/objects", store_dir);
+        let first_shard = fs::read_dir(&objects_dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let object_path = fs::read_dir(&first_shard)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut bytes = fs::read(&object_path).unwrap();
+        bytes[0] ^= 0xFF;
+        fs::write(&object_path, &bytes).unwrap();
+
+        let err = open_dedup(&store_dir, 0, 0).unwrap_err();
+        assert!(err.to_string().contains("content hash"));
+
+        fs::remove_dir_all(&store_dir).unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+/// Structured errors for the `.linear` reader/writer, carrying the
+/// offending values instead of only a formatted message so callers can
+/// match on the failure and inspect what was actually found.
+#[derive(Debug, Error)]
+pub enum LinearError {
+    #[error("bad linear signature: found {found}")]
+    BadSignature { found: i64 },
+    #[error("bad linear footer signature: found {found}")]
+    BadFooterSignature { found: i64 },
+    #[error("unsupported linear version: {0}")]
+    UnsupportedVersion(i8),
+    #[error("size mismatch: expected {expected}, got {got}")]
+    SizeMismatch { expected: i32, got: i32 },
+    #[error("chunk count mismatch: header says {header}, actual {actual}")]
+    ChunkCountMismatch { header: i16, actual: i16 },
+    #[error("datahash mismatch: expected {expected}, computed {computed}")]
+    DatahashMismatch { expected: i64, computed: i64 },
+    #[error("invalid region coordinates in path: {0}")]
+    InvalidPath(#[from] std::num::ParseIntError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("zstd (de)compression failed: {0}")]
+    Zstd(std::io::Error),
+}
@@ -0,0 +1,335 @@
+//! Per-chunk block-compressed linear format (version 3).
+//!
+//! Unlike v1/v2, where the whole 1024-chunk body is one zstd stream, each
+//! chunk here is compressed independently and indexed, so a single chunk
+//! can be read in O(chunk) instead of O(region) — no `decode_all` of the
+//! rest of the region required.
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, Cursor, SeekFrom};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use zstd::stream::decode_all;
+use zstd::stream::encode_all;
+
+use crate::{
+    datahash, Chunk, Region, LINEAR_SIGNATURE, LINEAR_VERSION_BLOCKED, NO_DATAHASH,
+};
+
+const INDEX_ENTRY_SIZE: usize = 28; // offset(8) + compressed_len(4) + raw_len(4) + timestamp(4) + hash(8)
+
+struct ChunkIndexEntry {
+    offset: u64,
+    compressed_length: u32,
+    raw_length: u32,
+    timestamp: i32,
+    /// `datahash` of this chunk's own decompressed bytes, checked by
+    /// `get_chunk` when it decodes the block. Keeping this per-chunk (rather
+    /// than one hash over the whole region) is what lets `get_chunk` stay
+    /// O(chunk): verifying it never requires touching any other chunk.
+    hash: i64,
+}
+
+/// A handle on an open v3 `.linear` file that decodes chunks on demand
+/// instead of eagerly decoding the whole region.
+pub struct LazyRegion {
+    file: File,
+    pub region_x: usize,
+    pub region_z: usize,
+    pub newest_timestamp: i64,
+    index: Vec<ChunkIndexEntry>,
+    data_start: u64,
+}
+
+impl LazyRegion {
+    /// Maps world `(x, z)` to an index-table slot, rejecting coordinates
+    /// that don't belong to this region instead of silently wrapping into
+    /// someone else's chunk via the `% 32` index math.
+    fn chunk_index(&self, x: usize, z: usize) -> Result<usize, Box<dyn Error>> {
+        if x / 32 != self.region_x || z / 32 != self.region_z {
+            return Err(format!(
+                "Chunk ({}, {}) is not in region ({}, {})",
+                x, z, self.region_x, self.region_z
+            )
+            .into());
+        }
+        Ok(x % 32 + (z % 32) * 32)
+    }
+
+    /// Returns the stored timestamp for a chunk without decoding it.
+    pub fn timestamp(&self, x: usize, z: usize) -> Result<i32, Box<dyn Error>> {
+        Ok(self.index[self.chunk_index(x, z)?].timestamp)
+    }
+
+    /// Decodes and returns a single chunk, seeking straight to its
+    /// compressed block instead of decoding the whole region.
+    pub fn get_chunk(&mut self, x: usize, z: usize) -> Result<Option<Chunk>, Box<dyn Error>> {
+        let i = self.chunk_index(x, z)?;
+        let entry = &self.index[i];
+        if entry.compressed_length == 0 {
+            return Ok(None);
+        }
+
+        self.file
+            .seek(SeekFrom::Start(self.data_start + entry.offset))?;
+        let mut raw = vec![0u8; entry.compressed_length as usize];
+        self.file.read_exact(&mut raw)?;
+        let raw_chunk = decode_all(Cursor::new(&raw))?;
+
+        if raw_chunk.len() != entry.raw_length as usize {
+            return Err(format!(
+                "Chunk ({}, {}): decoded size {} doesn't match stored size {}",
+                x,
+                z,
+                raw_chunk.len(),
+                entry.raw_length
+            )
+            .into());
+        }
+        if datahash(&raw_chunk) != entry.hash {
+            return Err(format!(
+                "Chunk ({}, {}): datahash mismatch, decoded block is corrupt",
+                x, z
+            )
+            .into());
+        }
+
+        Ok(Some(Chunk { raw_chunk, x, z }))
+    }
+}
+
+/// Opens a v3 `.linear` file lazily: the index is read up front, but chunk
+/// bodies are only decoded by [`LazyRegion::get_chunk`].
+pub fn open_linear_lazy(path: &str) -> Result<LazyRegion, Box<dyn Error>> {
+    let coords: Vec<&str> = path.split('/').next_back().unwrap().split('.').collect();
+    let region_x: usize = coords[1].parse::<usize>()?;
+    let region_z: usize = coords[2].parse::<usize>()?;
+
+    let file = File::open(path)?;
+    let mut buffer = BufReader::new(file);
+
+    let signature = buffer.read_i64::<BigEndian>()?;
+    let version = buffer.read_i8()?;
+    let newest_timestamp = buffer.read_i64::<BigEndian>()?;
+    // Skip compression level (Byte): Unused
+    buffer.seek(SeekFrom::Current(1))?;
+    let _chunk_count = buffer.read_i16::<BigEndian>()?;
+    let index_and_data_length = buffer.read_i32::<BigEndian>()?;
+    // Unlike v1/v2's whole-body datahash, v3 verifies corruption per chunk
+    // (see `ChunkIndexEntry::hash`), so this field is read only to advance
+    // the cursor to the index.
+    let _stored_datahash = buffer.read_i64::<BigEndian>()?;
+
+    if signature != LINEAR_SIGNATURE {
+        return Err(format!("Invalid signature: {}", signature).into());
+    }
+    if version != LINEAR_VERSION_BLOCKED {
+        return Err(format!(
+            "Invalid version for lazy reader: {} (expected {})",
+            version, LINEAR_VERSION_BLOCKED
+        )
+        .into());
+    }
+
+    let mut index = Vec::with_capacity(1024);
+    for _ in 0..1024 {
+        let offset = buffer.read_u64::<BigEndian>()?;
+        let compressed_length = buffer.read_u32::<BigEndian>()?;
+        let raw_length = buffer.read_u32::<BigEndian>()?;
+        let timestamp = buffer.read_i32::<BigEndian>()?;
+        let hash = buffer.read_i64::<BigEndian>()?;
+        index.push(ChunkIndexEntry {
+            offset,
+            compressed_length,
+            raw_length,
+            timestamp,
+            hash,
+        });
+    }
+
+    // Don't read the data blob here: that would force O(region) work (a
+    // decode per chunk) at open() time, defeating the point of the lazy
+    // per-chunk format. Only seek past it to check the footer signature;
+    // per-chunk corruption is instead caught by `get_chunk`, which already
+    // verifies the decoded length and datahash of the one block it reads.
+    let data_start = buffer.stream_position()?;
+    let data_length = index_and_data_length as usize - 1024 * INDEX_ENTRY_SIZE;
+    buffer.seek(SeekFrom::Start(data_start + data_length as u64))?;
+
+    let signature_footer = buffer.read_i64::<BigEndian>()?;
+    if signature_footer != LINEAR_SIGNATURE {
+        return Err(format!("Invalid footer signature: {}", signature_footer).into());
+    }
+
+    let file = buffer.into_inner();
+
+    Ok(LazyRegion {
+        file,
+        region_x,
+        region_z,
+        newest_timestamp,
+        index,
+        data_start,
+    })
+}
+
+impl Region {
+    /// Writes this region out as a v3 `.linear` file: chunks are
+    /// compressed independently so a reader can later seek to and decode
+    /// a single one via [`open_linear_lazy`] + [`LazyRegion::get_chunk`].
+    pub fn write_linear_blocked(
+        &self,
+        dir: &str,
+        compression_level: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = format!("{}/r.{}.{}.linear", dir, self.region_x, self.region_z);
+        let wip_path = format!("{}/r.{}.{}.linear.wip", dir, self.region_x, self.region_z);
+
+        let mut index: Vec<(u64, u32, u32, i32, i64)> = Vec::with_capacity(1024);
+        let mut data: Vec<u8> = Vec::new();
+
+        for i in 0..1024 {
+            if let Some(chunk) = &self.chunks[i] {
+                let encoded = encode_all(Cursor::new(&chunk.raw_chunk), compression_level)?;
+                let offset = data.len() as u64;
+                index.push((
+                    offset,
+                    encoded.len() as u32,
+                    chunk.raw_chunk.len() as u32,
+                    self.timestamps[i],
+                    datahash(&chunk.raw_chunk),
+                ));
+                data.extend_from_slice(&encoded);
+            } else {
+                index.push((0, 0, 0, 0, NO_DATAHASH));
+            }
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&wip_path)?;
+        let mut buffer = BufWriter::new(file);
+
+        let chunk_count = self.count_chunks();
+        let index_and_data_length = (index.len() * INDEX_ENTRY_SIZE + data.len()) as i32;
+
+        buffer.write_i64::<BigEndian>(LINEAR_SIGNATURE)?;
+        buffer.write_i8(LINEAR_VERSION_BLOCKED)?;
+        buffer.write_i64::<BigEndian>(self.newest_timestamp)?;
+        buffer.write_i8(compression_level as i8)?;
+        buffer.write_i16::<BigEndian>(chunk_count)?;
+        buffer.write_i32::<BigEndian>(index_and_data_length)?;
+        // No whole-region datahash here: each index entry below carries its
+        // own chunk's hash, which is what `get_chunk` verifies against.
+        buffer.write_i64::<BigEndian>(NO_DATAHASH)?;
+
+        for (offset, compressed_length, raw_length, timestamp, hash) in &index {
+            buffer.write_u64::<BigEndian>(*offset)?;
+            buffer.write_u32::<BigEndian>(*compressed_length)?;
+            buffer.write_u32::<BigEndian>(*raw_length)?;
+            buffer.write_i32::<BigEndian>(*timestamp)?;
+            buffer.write_i64::<BigEndian>(*hash)?;
+        }
+        buffer.write_all(&data)?;
+
+        buffer.write_i64::<BigEndian>(LINEAR_SIGNATURE)?;
+
+        buffer.flush()?;
+        fs::rename(wip_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_with_chunks(region_x: usize, region_z: usize) -> Region {
+        let mut chunks = vec![None; 1024];
+        let mut timestamps = vec![0i32; 1024];
+        for i in 0..5usize {
+            chunks[i] = Some(Chunk {
+                raw_chunk: (0..64).map(|b| (b + i) as u8).collect(),
+                x: 32 * region_x + i % 32,
+                z: 32 * region_z + i / 32,
+            });
+            timestamps[i] = 100 + i as i32;
+        }
+        Region {
+            chunks,
+            region_x,
+            region_z,
+            timestamps,
+            newest_timestamp: 104,
+        }
+    }
+
+    #[test]
+    fn write_linear_blocked_open_linear_lazy_roundtrip() {
+        let dir = format!("{}/linearify_test_lazy_roundtrip", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_linear_blocked(&dir, 3).unwrap();
+
+        let mut lazy = open_linear_lazy(&format!("{}/r.0.0.linear", dir)).unwrap();
+        for i in 0..5usize {
+            let x = i % 32;
+            let z = i / 32;
+            let original = region.chunks[i].as_ref().unwrap();
+            let chunk = lazy.get_chunk(x, z).unwrap().unwrap();
+            assert_eq!(chunk.raw_chunk, original.raw_chunk);
+            assert_eq!(lazy.timestamp(x, z).unwrap(), 100 + i as i32);
+        }
+        assert!(lazy.get_chunk(10, 10).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_chunk_rejects_coordinates_outside_its_region() {
+        let dir = format!("{}/linearify_test_lazy_bounds", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_linear_blocked(&dir, 3).unwrap();
+
+        let mut lazy = open_linear_lazy(&format!("{}/r.0.0.linear", dir)).unwrap();
+        assert!(lazy.get_chunk(32 * 5, 32 * 5).is_err());
+        assert!(lazy.timestamp(0, 32 * 3).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn get_chunk_detects_per_chunk_corruption_without_eager_decode() {
+        let dir = format!("{}/linearify_test_lazy_corrupt", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_linear_blocked(&dir, 3).unwrap();
+
+        let path = format!("{}/r.0.0.linear", dir);
+        let mut bytes = fs::read(&path).unwrap();
+        // Header (32 bytes) + 1024 index entries precede the data blob;
+        // flip a byte right at its start, inside chunk 0's compressed block.
+        let data_start = 32 + 1024 * INDEX_ENTRY_SIZE;
+        bytes[data_start] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+
+        // Opening must stay cheap and must NOT eagerly fail on this.
+        let mut lazy = open_linear_lazy(&path).unwrap();
+        assert!(lazy.get_chunk(0, 0).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
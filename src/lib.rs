@@ -7,13 +7,43 @@ use std::io::prelude::*;
 use std::io::{BufReader, BufWriter, Cursor, Read, SeekFrom};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use xxhash_rust::xxh3::xxh3_64;
 use zstd::stream::decode_all;
 use zstd::stream::encode_all;
 
+mod anvil;
+mod dedup;
+mod error;
+mod lazy;
+pub use anvil::open_anvil;
+pub use dedup::{open_dedup, write_dedup};
+pub use error::LinearError;
+pub use lazy::{open_linear_lazy, LazyRegion};
+
 const LINEAR_SIGNATURE: i64 = -4323716122432332390;
 const LINEAR_VERSION: i8 = 2;
 const LINEAR_SUPPORTED: [i8; 2] = [1, 2];
+// Per-chunk block-compressed layout (see `lazy`). Not accepted by
+// `open_linear`, which only understands the monolithic v1/v2 stream.
+const LINEAR_VERSION_BLOCKED: i8 = 3;
 const HEADER_SIZE: i32 = 8192;
+// Sentinel stored in the datahash field by writers that never computed one
+// (version 2 files written before the hash existed); open_linear treats it
+// as "no hash" and skips verification.
+const NO_DATAHASH: i64 = 0;
+
+/// Hashes the uncompressed chunk buffer with xxh3-64 for storage in the
+/// header's datahash field.
+fn datahash(raw_data: &[u8]) -> i64 {
+    let hash = xxh3_64(raw_data) as i64;
+    // Never collide with the "no hash" sentinel.
+    if hash == NO_DATAHASH {
+        1
+    } else {
+        hash
+    }
+}
 
 #[derive(Clone)]
 pub struct Chunk {
@@ -43,14 +73,14 @@ pub struct Region {
 }
 
 impl fmt::Display for Region {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (index, chunk) in self.chunks.iter().enumerate() {
             match chunk {
-                Some(_) => print!("■"),
-                None => print!("□"),
+                Some(_) => write!(f, "■")?,
+                None => write!(f, "□")?,
             }
             if index % 32 == 31 {
-                println!();
+                writeln!(f)?;
             }
         }
         Ok(())
@@ -62,16 +92,10 @@ impl Region {
         self.chunks.iter().filter(|&chunk| chunk.is_some()).count() as i16
     }
 
-    pub fn write_linear(&self, dir: &str, compression_level: i32) -> Result<(), Box<dyn Error>> {
-        let path = format!("{}/r.{}.{}.linear", dir, self.region_x, self.region_z);
-        let wip_path = format!("{}/r.{}.{}.linear.wip", dir, self.region_x, self.region_z);
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&wip_path)?;
-
-        // Get chunks data
+    /// Builds the `HEADER_SIZE`-byte size/timestamp table followed by the
+    /// concatenated raw chunk bytes — the uncompressed body shared by the
+    /// monolithic `.linear` stream and the dedup store's segment input.
+    fn build_raw_data(&self) -> Vec<u8> {
         let mut raw_data: Vec<u8> = Vec::new();
         for i in 0..1024 {
             if let Some(chunk) = &self.chunks[i] {
@@ -90,8 +114,21 @@ impl Region {
                 raw_data.extend_from_slice(chunk.raw_chunk.as_slice());
             }
         }
+        raw_data
+    }
+
+    pub fn write_linear(&self, dir: &str, compression_level: i32) -> Result<(), LinearError> {
+        let path = format!("{}/r.{}.{}.linear", dir, self.region_x, self.region_z);
+        let wip_path = format!("{}/r.{}.{}.linear.wip", dir, self.region_x, self.region_z);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&wip_path)?;
+
+        let raw_data = self.build_raw_data();
         let raw_cursor = Cursor::new(&raw_data);
-        let encoded: Vec<u8> = encode_all(raw_cursor, compression_level)?; // Encode it
+        let encoded: Vec<u8> = encode_all(raw_cursor, compression_level).map_err(LinearError::Zstd)?; // Encode it
 
         // Write file
         let mut buffer = BufWriter::new(file);
@@ -103,7 +140,7 @@ impl Region {
         buffer.write_i8(compression_level as i8)?; // Compression level
         buffer.write_i16::<BigEndian>(chunk_count)?; // Chunk count
         buffer.write_i32::<BigEndian>(encoded.len() as i32)?; // Compressed size
-        buffer.write_i64::<BigEndian>(0)?; // Datahash: skip, unimplemented
+        buffer.write_i64::<BigEndian>(datahash(&raw_data))?; // Datahash
 
         // Chunk data
         buffer.write_all(encoded.as_slice())?;
@@ -116,10 +153,153 @@ impl Region {
         fs::rename(wip_path, path)?;
         Ok(())
     }
+
+    /// Walks every present chunk, checks that it decompresses to a sane NBT
+    /// root, and tallies the result. With `fix = true`, chunks that can't be
+    /// recovered are dropped (set to `None`, timestamp zeroed) so a
+    /// subsequent `write_linear` produces a clean region.
+    pub fn scan(&mut self, fix: bool) -> ScanStatistics {
+        let mut stats = ScanStatistics::default();
+
+        for i in 0..1024 {
+            let Some(chunk) = &self.chunks[i] else {
+                continue;
+            };
+            stats.total_chunks += 1;
+
+            match validate_chunk_nbt(&chunk.raw_chunk) {
+                Ok(()) => stats.valid_chunks += 1,
+                Err(ChunkValidationError::Unrecoverable(reason)) => {
+                    stats.corrupt_chunks += 1;
+                    stats.unrecoverable_chunks += 1;
+                    stats.failures.push((chunk.x, chunk.z, reason));
+                    if fix {
+                        self.chunks[i] = None;
+                        self.timestamps[i] = 0;
+                    }
+                }
+                Err(ChunkValidationError::Recoverable(reason)) => {
+                    stats.corrupt_chunks += 1;
+                    stats.recoverable_chunks += 1;
+                    stats.failures.push((chunk.x, chunk.z, reason));
+                }
+            }
+        }
+
+        stats
+    }
+}
+
+/// Tallies produced by [`Region::scan`]. `failures` carries the reason for
+/// every corrupt chunk, keyed by its world `(x, z)`, so callers can report
+/// (or log) which chunks failed and why instead of only a count.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanStatistics {
+    pub total_chunks: usize,
+    pub valid_chunks: usize,
+    pub corrupt_chunks: usize,
+    pub recoverable_chunks: usize,
+    pub unrecoverable_chunks: usize,
+    pub failures: Vec<(usize, usize, String)>,
+}
+
+#[derive(Debug)]
+enum ChunkValidationError {
+    /// The compressed stream itself is broken; the chunk can't be salvaged.
+    Unrecoverable(String),
+    /// The stream decompresses but the NBT root doesn't look right; kept
+    /// around in case a future reader can still make sense of it.
+    Recoverable(String),
 }
 
-pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
-    let coords: Vec<&str> = path.split('/').last().unwrap().split('.').collect();
+impl fmt::Display for ChunkValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkValidationError::Unrecoverable(msg) => write!(f, "{}", msg),
+            ChunkValidationError::Recoverable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+const NBT_TAG_COMPOUND: u8 = 0x0A;
+
+/// Anvil's per-chunk compression scheme byte (1 = gzip, 2 = zlib).
+/// `Chunk.raw_chunk` never stores this byte itself, so it's recovered from
+/// the payload's own magic number when something needs to know it (e.g.
+/// repacking into `.mca`).
+pub(crate) fn detect_compression_scheme(raw_chunk: &[u8]) -> Option<u8> {
+    match raw_chunk {
+        [0x1F, 0x8B, ..] => Some(1), // Gzip
+        [0x78, ..] => Some(2),       // Zlib
+        _ => None,
+    }
+}
+
+/// Decompresses `raw_chunk` (detecting the gzip/zlib magic number) and
+/// checks that it starts with a `TAG_Compound` root.
+fn validate_chunk_nbt(raw_chunk: &[u8]) -> Result<(), ChunkValidationError> {
+    let mut decompressed = Vec::new();
+    let read_result = match detect_compression_scheme(raw_chunk) {
+        Some(1) => GzDecoder::new(raw_chunk).read_to_end(&mut decompressed),
+        Some(2) => ZlibDecoder::new(raw_chunk).read_to_end(&mut decompressed),
+        _ => {
+            return Err(ChunkValidationError::Unrecoverable(
+                "unrecognized compression magic number".into(),
+            ))
+        }
+    };
+
+    if let Err(e) = read_result {
+        return Err(ChunkValidationError::Unrecoverable(format!(
+            "failed to decompress: {}",
+            e
+        )));
+    }
+
+    match decompressed.first() {
+        Some(&NBT_TAG_COMPOUND) => Ok(()),
+        Some(&other) => Err(ChunkValidationError::Recoverable(format!(
+            "root tag is {:#x}, expected TAG_Compound",
+            other
+        ))),
+        None => Err(ChunkValidationError::Unrecoverable("empty NBT body".into())),
+    }
+}
+
+/// Per-region scan results, keyed by `(region_x, region_z)`.
+pub type RegionScanResults = Vec<((usize, usize), ScanStatistics)>;
+
+/// Runs [`Region::scan`] over every `.linear` file in `dir`, rewriting
+/// fixed regions in place when `fix` is set. Returns the per-region stats
+/// keyed by `(region_x, region_z)`.
+pub fn scan_directory(
+    dir: &str,
+    fix: bool,
+    compression_level: i32,
+) -> Result<RegionScanResults, Box<dyn Error>> {
+    let mut results = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("linear") {
+            continue;
+        }
+
+        let path_str = path.to_str().ok_or("Non-UTF8 path")?;
+        let mut region = open_linear(path_str)?;
+        let stats = region.scan(fix);
+        if fix && stats.unrecoverable_chunks > 0 {
+            region.write_linear(dir, compression_level)?;
+        }
+        results.push(((region.region_x, region.region_z), stats));
+    }
+
+    Ok(results)
+}
+
+pub fn open_linear(path: &str) -> Result<Region, LinearError> {
+    let coords: Vec<&str> = path.split('/').next_back().unwrap().split('.').collect();
     let region_x: usize = coords[1].parse::<usize>()?;
     let region_z: usize = coords[2].parse::<usize>()?;
 
@@ -130,7 +310,7 @@ pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
     // Go to the end 8 bytes before to read signature footer
     buffer.seek(SeekFrom::End(-8))?;
     let signature_footer = buffer.read_i64::<BigEndian>()?;
-    buffer.seek(SeekFrom::Start(0))?; 
+    buffer.seek(SeekFrom::Start(0))?;
     let signature = buffer.read_i64::<BigEndian>()?;
     let version = buffer.read_i8()?;
     let newest_timestamp = buffer.read_i64::<BigEndian>()?;
@@ -138,18 +318,19 @@ pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
     buffer.seek(SeekFrom::Current(1))?;
     let chunk_count = buffer.read_i16::<BigEndian>()?;
     let compressed_length = buffer.read_i32::<BigEndian>()?;
-    // Skip datahash (Long): Unused
-    buffer.seek(SeekFrom::Current(8))?;
+    let stored_datahash = buffer.read_i64::<BigEndian>()?;
 
     // Verify data
     if signature != LINEAR_SIGNATURE {
-        return Err(format!("Invalid signature: {}", signature).into());
+        return Err(LinearError::BadSignature { found: signature });
     }
-    if !LINEAR_SUPPORTED.iter().any(|&num| num == version) {
-        return Err(format!("Invalid version: {}", version).into());
+    if !LINEAR_SUPPORTED.contains(&version) {
+        return Err(LinearError::UnsupportedVersion(version));
     }
     if signature_footer != LINEAR_SIGNATURE {
-        return Err(format!("Invalid footer signature: {}", signature_footer).into());
+        return Err(LinearError::BadFooterSignature {
+            found: signature_footer,
+        });
     }
 
     // Read raw chunk
@@ -157,10 +338,48 @@ pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
     buffer.read_exact(&mut raw)?;
     let raw_cursor = Cursor::new(&raw);
     // Decode data
-    let decoded: Vec<u8> = decode_all(raw_cursor)?;
-    let mut cursor = Cursor::new(&decoded);
+    let decoded: Vec<u8> = decode_all(raw_cursor).map_err(LinearError::Zstd)?;
+
+    // Version 1 never wrote this field (so it's unreliable regardless of
+    // what's in it), and regions written before this field existed store 0
+    // (NO_DATAHASH); skip verification for either rather than reject them.
+    let computed_datahash = datahash(&decoded);
+    if version != 1 && stored_datahash != NO_DATAHASH && computed_datahash != stored_datahash {
+        return Err(LinearError::DatahashMismatch {
+            expected: stored_datahash,
+            computed: computed_datahash,
+        });
+    }
+
+    let (chunks, timestamps, real_chunk_count) = decode_region_body(region_x, region_z, &decoded)?;
+
+    if real_chunk_count != chunk_count {
+        return Err(LinearError::ChunkCountMismatch {
+            header: chunk_count,
+            actual: real_chunk_count,
+        });
+    }
+
+    Ok(Region {
+        chunks,
+        region_x,
+        region_z,
+        timestamps,
+        newest_timestamp,
+    })
+}
+
+/// Chunks, their timestamps, and the real (non-`None`) chunk count parsed
+/// from a decoded region body.
+type RegionBody = (Vec<Option<Chunk>>, Vec<i32>, i16);
+
+/// Parses the `HEADER_SIZE`-byte size/timestamp table followed by the
+/// concatenated chunk bytes that make up a region body once decompressed
+/// (or, for the dedup store, reassembled from segments). Shared by
+/// `open_linear` and `dedup::open_dedup`.
+fn decode_region_body(region_x: usize, region_z: usize, body: &[u8]) -> Result<RegionBody, LinearError> {
+    let mut cursor = Cursor::new(body);
 
-    // Start deserializing
     let mut sizes: Vec<usize> = Vec::new();
     let mut timestamps: Vec<i32> = Vec::new();
     let mut chunks: Vec<Option<Chunk>> = vec![None; 1024];
@@ -177,12 +396,11 @@ pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
     }
 
     // Check if chunk data is corrupted
-    if total_size + HEADER_SIZE != decoded.len() as i32 {
-        return Err("Invalid decompressed size: {}".into());
-    }
-
-    if real_chunk_count != chunk_count {
-        return Err(format!("Invalid chunk count {}/{}", chunk_count, real_chunk_count).into());
+    if total_size + HEADER_SIZE != body.len() as i32 {
+        return Err(LinearError::SizeMismatch {
+            expected: body.len() as i32 - HEADER_SIZE,
+            got: total_size,
+        });
     }
 
     // Save raw chunk data
@@ -198,11 +416,72 @@ pub fn open_linear(path: &str) -> Result<Region, Box<dyn Error>> {
         }
     }
 
-    Ok(Region {
-        chunks,
-        region_x,
-        region_z,
-        timestamps,
-        newest_timestamp,
-    })
+    Ok((chunks, timestamps, real_chunk_count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_with_chunks(region_x: usize, region_z: usize) -> Region {
+        let mut chunks = vec![None; 1024];
+        let mut timestamps = vec![0i32; 1024];
+        for i in 0..5usize {
+            chunks[i] = Some(Chunk {
+                raw_chunk: (0..64).map(|b| (b + i) as u8).collect(),
+                x: 32 * region_x + i % 32,
+                z: 32 * region_z + i / 32,
+            });
+            timestamps[i] = 100 + i as i32;
+        }
+        Region {
+            chunks,
+            region_x,
+            region_z,
+            timestamps,
+            newest_timestamp: 104,
+        }
+    }
+
+    #[test]
+    fn write_linear_open_linear_roundtrip() {
+        let dir = format!("{}/linearify_test_lib_roundtrip", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_linear(&dir, 3).unwrap();
+
+        let reopened = open_linear(&format!("{}/r.0.0.linear", dir)).unwrap();
+        for i in 0..5usize {
+            let original = region.chunks[i].as_ref().unwrap();
+            let read_back = reopened.chunks[i].as_ref().unwrap();
+            assert_eq!(original.raw_chunk, read_back.raw_chunk);
+        }
+        assert_eq!(reopened.newest_timestamp, region.newest_timestamp);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_linear_rejects_datahash_mismatch() {
+        let dir = format!("{}/linearify_test_lib_datahash", std::env::temp_dir().display());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let region = region_with_chunks(0, 0);
+        region.write_linear(&dir, 3).unwrap();
+
+        let path = format!("{}/r.0.0.linear", dir);
+        let mut bytes = fs::read(&path).unwrap();
+        // Flip a byte inside the stored datahash field (offset 24..32), so
+        // the compressed payload still decodes fine but no longer matches.
+        bytes[24] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+
+        let err = open_linear(&path).unwrap_err();
+        assert!(matches!(err, LinearError::DatahashMismatch { .. }));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }